@@ -2,16 +2,22 @@
 
 use std::{
     collections::BTreeMap,
+    env,
     ffi::OsStr,
     future::Future,
+    io,
     path::{Path, PathBuf},
+    pin::Pin,
     process::Stdio,
     result,
     str::{from_utf8, FromStr},
+    thread::available_parallelism,
 };
 
 use anyhow::{anyhow, Context as _};
 use cast;
+use chrono::{DateTime, Utc};
+use futures::stream::{FuturesUnordered, StreamExt};
 use log::debug;
 use num::rational::Ratio;
 use regex::Regex;
@@ -30,6 +36,8 @@ use crate::{
     Result,
 };
 
+mod iso_bmff;
+
 /// The identifier of a data stream within a media container
 /// format. This is used to refer to individual audio or video
 /// streams within a file.
@@ -72,6 +80,25 @@ impl Id3Metadata {
     }
 }
 
+/// Parse a container `track` tag such as `"3"` or `"3/12"` into a
+/// `(track, total)` pair, as used by [`Id3Metadata::track_number`].
+fn parse_track_number(s: &str) -> Option<(usize, usize)> {
+    let re = Regex::new(r"^(\d+)(?:/(\d+))?$").unwrap();
+    let cap = re.captures(s)?;
+    let track: usize = cap.get(1)?.as_str().parse().ok()?;
+    let total = match cap.get(2) {
+        Some(m) => m.as_str().parse().ok()?,
+        None => track,
+    };
+    Some((track, total))
+}
+
+/// Parse a [`Stream::id`] such as `"0x2"` into the numeric `track_ID` it
+/// encodes.
+fn parse_hex_track_id(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
 /// A picture. This is basically the same as [`audiotags::types::Picture`], except
 /// that it's `'static`.
 pub struct Picture {
@@ -137,14 +164,93 @@ impl<'de> Deserialize<'de> for Fraction {
     }
 }
 
+/// A numeric type ffprobe might report as a plain string, e.g.
+/// `"48000.000000"` for an integer sample rate.
+trait FromFfprobeStr: Sized {
+    fn from_ffprobe_str(s: &str) -> Option<Self>;
+}
+
+impl FromFfprobeStr for f32 {
+    fn from_ffprobe_str(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+impl FromFfprobeStr for u32 {
+    fn from_ffprobe_str(s: &str) -> Option<Self> {
+        s.parse().ok().or_else(|| Some(s.parse::<f64>().ok()?.round() as u32))
+    }
+}
+
+impl FromFfprobeStr for u64 {
+    fn from_ffprobe_str(s: &str) -> Option<Self> {
+        s.parse().ok().or_else(|| Some(s.parse::<f64>().ok()?.round() as u64))
+    }
+}
+
+/// Deserialize a field which ffprobe reports as a numeric string (and
+/// sometimes as the literal string `"N/A"`), treating anything we can't
+/// parse as `None` rather than an error.
+fn deserialize_opt_from_str<'de, D, T>(d: D) -> result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromFfprobeStr,
+{
+    Ok(Option::<String>::deserialize(d)?.and_then(|s| T::from_ffprobe_str(&s)))
+}
+
+/// One entry of a stream's edit list (its `edts`/`elst` box), fully
+/// resolved to seconds. An edit list maps the *presentation* timeline --
+/// the one subtitle timestamps are aligned to -- to the underlying *media*
+/// timeline, most commonly to discard AAC encoder priming samples so that
+/// playback starts at `media_start > 0` without shifting where subtitles
+/// land.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EditListEntry {
+    /// Where this segment starts on the presentation timeline, in
+    /// seconds.
+    pub presentation_start: f32,
+    /// How long this segment lasts on the presentation timeline, in
+    /// seconds.
+    pub duration: f32,
+    /// Where this segment starts on the underlying media timeline, in
+    /// seconds.
+    pub media_start: f32,
+    /// This segment's playback rate relative to the media timeline
+    /// (almost always `1.0`).
+    pub media_rate: f32,
+}
+
 /// An individual content stream within a video.
 #[derive(Clone, Debug, Deserialize)]
 #[allow(missing_docs)]
 pub struct Stream {
     pub index: usize,
+    /// This container's own identifier for the track backing this stream
+    /// (for ISO-BMFF, its `tkhd` box's `track_ID`), reported in hex, e.g.
+    /// `"0x2"`. `ffprobe` surfaces this as a stream's `id` field for
+    /// formats that have one; used to match a stream back up to the
+    /// corresponding `trak` when filling in [`Stream::edit_list`].
+    #[serde(default)]
+    pub id: Option<String>,
     pub codec_type: CodecType,
+    /// The codec's short name, e.g. `"aac"` or `"h264"`.
+    pub codec_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub duration: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub bit_rate: Option<u64>,
     pub tags: Option<BTreeMap<String, String>>,
     pub disposition: Option<BTreeMap<String, u32>>,
+    /// This stream's edit list, if it has one. `ffprobe` doesn't surface
+    /// this directly, so it's populated separately via our built-in
+    /// ISO-BMFF reader regardless of which [`ProbeBackend`] is in use; it
+    /// stays `None` for non-ISO-BMFF containers.
+    #[serde(skip, default)]
+    pub edit_list: Option<Vec<EditListEntry>>,
 }
 
 impl Stream {
@@ -161,12 +267,58 @@ impl Stream {
     /// probably album cover art attached to a music file, and we'll need to
     /// handle it specially.
     pub fn is_attached_pic(&self) -> bool {
+        self.has_disposition("attached_pic")
+    }
+
+    /// Is this stream flagged as the default stream of its type?
+    pub fn is_default(&self) -> bool {
+        self.has_disposition("default")
+    }
+
+    /// Is this stream flagged as "forced" (e.g. a subtitle track that
+    /// should be shown even when subtitles are otherwise off, such as for
+    /// foreign-language dialogue)?
+    pub fn is_forced(&self) -> bool {
+        self.has_disposition("forced")
+    }
+
+    fn has_disposition(&self, flag: &str) -> bool {
         self.disposition
             .as_ref()
-            .and_then(|d| d.get("attached_pic"))
+            .and_then(|d| d.get(flag))
             .map(|&v| v == 1)
             .unwrap_or(false)
     }
+
+    /// Map a time on the presentation timeline (the one subtitle
+    /// timestamps are aligned to) to the corresponding time on this
+    /// stream's underlying media timeline, using its edit list. Falls
+    /// back to the identity mapping if this stream has no edit list. If
+    /// `presentation_time` falls outside every segment, it is mapped
+    /// relative to the last segment.
+    pub fn media_time_for_presentation_time(&self, presentation_time: f32) -> f32 {
+        let Some(edit_list) = &self.edit_list else {
+            return presentation_time;
+        };
+        // Find the segment containing `presentation_time`, or fall back
+        // to the last segment if we're past the end of the edit list
+        // (e.g. because of small rounding differences at the very end of
+        // the clip).
+        let segment = edit_list
+            .iter()
+            .find(|e| {
+                presentation_time >= e.presentation_start
+                    && presentation_time < e.presentation_start + e.duration
+            })
+            .or_else(|| edit_list.last());
+        match segment {
+            Some(segment) => {
+                let offset_in_segment = presentation_time - segment.presentation_start;
+                segment.media_start + offset_in_segment * segment.media_rate
+            }
+            None => presentation_time,
+        }
+    }
 }
 
 #[test]
@@ -194,7 +346,127 @@ fn test_stream_decode() {
 ";
     let stream: Stream = serde_json::from_str(json).unwrap();
     assert_eq!(CodecType::Audio, stream.codec_type);
-    assert_eq!(Some(Lang::iso639("en").unwrap()), stream.language())
+    assert_eq!(Some(Lang::iso639("en").unwrap()), stream.language());
+    assert_eq!(Some("aac".to_owned()), stream.codec_name);
+    assert_eq!(Some(48000), stream.sample_rate);
+    assert_eq!(Some(2), stream.channels);
+    assert_eq!(None, stream.duration);
+}
+
+#[test]
+fn test_media_time_for_presentation_time() {
+    fn stream_with_edit_list(edit_list: Option<Vec<EditListEntry>>) -> Stream {
+        Stream {
+            index: 0,
+            id: None,
+            codec_type: CodecType::Audio,
+            codec_name: None,
+            sample_rate: None,
+            channels: None,
+            duration: None,
+            bit_rate: None,
+            tags: None,
+            disposition: None,
+            edit_list,
+        }
+    }
+
+    // No edit list: identity mapping.
+    let stream = stream_with_edit_list(None);
+    assert_eq!(1.5, stream.media_time_for_presentation_time(1.5));
+
+    // A single entry that discards the first 0.1s of media (e.g. AAC
+    // priming samples): presentation time 0.0 maps to media time 0.1, and
+    // times within the segment are shifted by the same offset.
+    let stream = stream_with_edit_list(Some(vec![EditListEntry {
+        presentation_start: 0.0,
+        duration: 10.0,
+        media_start: 0.1,
+        media_rate: 1.0,
+    }]));
+    assert_eq!(0.1, stream.media_time_for_presentation_time(0.0));
+    assert_eq!(1.1, stream.media_time_for_presentation_time(1.0));
+
+    // A time past the end of every segment falls back to the last segment,
+    // extrapolating from its start rather than snapping back to the
+    // beginning of the clip.
+    assert_eq!(10.1, stream.media_time_for_presentation_time(10.0));
+}
+
+/// A hardware-accelerated decode backend to ask ffmpeg to use instead of
+/// software decoding. This matters most when batch-extracting thousands of
+/// image frames from a long film, where GPU decode can dramatically cut
+/// extraction time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum HwAccel {
+    /// Always use the CPU software decoder.
+    #[default]
+    None,
+    /// Use VAAPI, available on Linux with supported Intel/AMD hardware.
+    Vaapi,
+    /// Use NVIDIA's NVDEC, via ffmpeg's `cuda` hwaccel.
+    Cuda,
+    /// Use Apple's VideoToolbox, available on macOS.
+    VideoToolbox,
+    /// Let ffmpeg auto-detect the best available hardware decoder.
+    Auto,
+}
+
+impl HwAccel {
+    /// The value to pass to ffmpeg's `-hwaccel` flag, or `None` if we
+    /// should stick with software decoding.
+    fn hwaccel_name(self) -> Option<&'static str> {
+        match self {
+            HwAccel::None => None,
+            HwAccel::Vaapi => Some("vaapi"),
+            HwAccel::Cuda => Some("cuda"),
+            HwAccel::VideoToolbox => Some("videotoolbox"),
+            HwAccel::Auto => Some("auto"),
+        }
+    }
+
+    /// The value to pass to ffmpeg's `-hwaccel_output_format` flag, for
+    /// backends that need it to keep decoded frames on the GPU instead of
+    /// copying them back to system memory on every frame.
+    fn hwaccel_output_format(self) -> Option<&'static str> {
+        match self {
+            HwAccel::Vaapi => Some("vaapi"),
+            HwAccel::Cuda => Some("cuda"),
+            HwAccel::None | HwAccel::VideoToolbox | HwAccel::Auto => None,
+        }
+    }
+
+    /// Add the `-hwaccel` (and, where needed, `-hwaccel_output_format`)
+    /// flags to `cmd`. These must be added before `-i`.
+    fn add_args(self, cmd: &mut Command) {
+        if let Some(name) = self.hwaccel_name() {
+            cmd.arg("-hwaccel").arg(name);
+            if let Some(format) = self.hwaccel_output_format() {
+                cmd.arg("-hwaccel_output_format").arg(format);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hwaccel_name_and_output_format() {
+    // `None` passes no flags at all.
+    assert_eq!(None, HwAccel::None.hwaccel_name());
+    assert_eq!(None, HwAccel::None.hwaccel_output_format());
+
+    // VAAPI and CUDA both need `-hwaccel_output_format` to keep decoded
+    // frames on the GPU.
+    assert_eq!(Some("vaapi"), HwAccel::Vaapi.hwaccel_name());
+    assert_eq!(Some("vaapi"), HwAccel::Vaapi.hwaccel_output_format());
+    assert_eq!(Some("cuda"), HwAccel::Cuda.hwaccel_name());
+    assert_eq!(Some("cuda"), HwAccel::Cuda.hwaccel_output_format());
+
+    // VideoToolbox and Auto pass `-hwaccel` but don't need an explicit
+    // output format.
+    assert_eq!(Some("videotoolbox"), HwAccel::VideoToolbox.hwaccel_name());
+    assert_eq!(None, HwAccel::VideoToolbox.hwaccel_output_format());
+    assert_eq!(Some("auto"), HwAccel::Auto.hwaccel_name());
+    assert_eq!(None, HwAccel::Auto.hwaccel_output_format());
 }
 
 /// What kind of image source does this file contain?
@@ -236,6 +508,34 @@ impl ExtractionSpec {
         }
     }
 
+    /// If this is an [`ExtractionSpec::Image`], snap its `time` to the
+    /// nearest entry in `scene_changes` that still falls within `period`
+    /// (typically the span of the subtitle the image illustrates). This
+    /// avoids grabbing whatever happens to be on screen at a dialogue's
+    /// start time, which is often a transition or black frame, in favor
+    /// of a representative frame from the same scene. Leaves any other
+    /// spec, or an `Image` with no scene change in range, unchanged.
+    pub fn snapped_to_scene_change(
+        &self,
+        scene_changes: &[f32],
+        period: Period,
+    ) -> ExtractionSpec {
+        match self {
+            &ExtractionSpec::Image { time } => {
+                let snapped = scene_changes
+                    .iter()
+                    .copied()
+                    .filter(|&t| t >= period.begin() && t <= period.end())
+                    .min_by(|a, b| {
+                        (a - time).abs().partial_cmp(&(b - time).abs()).unwrap()
+                    })
+                    .unwrap_or(time);
+                ExtractionSpec::Image { time: snapped }
+            }
+            other => other.clone(),
+        }
+    }
+
     /// Can we combine this extraction with others in a giant batch
     /// request?
     fn can_be_batched(&self) -> bool {
@@ -250,8 +550,12 @@ impl ExtractionSpec {
 
     /// Figure out what ffmpeg args we would need to extract the requested
     /// data.  Assume that the "fast seek" feature has been used to start
-    /// decoding at `time_base`.
-    fn add_args(&self, cmd: &mut Command, time_base: f32) {
+    /// decoding at `time_base`. `video` is used to map [`ExtractionSpec::Audio`]'s
+    /// period from the presentation timeline (what subtitle timestamps are
+    /// aligned to) to the underlying media timeline via the target
+    /// stream's edit list, so that extracted clips aren't offset by
+    /// encoder priming or other edits.
+    fn add_args(&self, cmd: &mut Command, video: &Video, time_base: f32) {
         match self {
             ExtractionSpec::Image { time } => {
                 let scale_filter =
@@ -274,15 +578,57 @@ impl ExtractionSpec {
                     cmd.arg("-map").arg(format!("0:{}", sid.0));
                 }
                 metadata.add_args(cmd);
+                let media_begin = video.media_time_for(*stream, period.begin());
+                let media_end = video.media_time_for(*stream, period.end());
                 cmd.arg("-ss")
-                    .arg(format!("{}", period.begin() - time_base))
+                    .arg(format!("{}", media_begin - time_base))
                     .arg("-t")
-                    .arg(format!("{}", period.duration()));
+                    .arg(format!("{}", media_end - media_begin));
             }
         }
     }
 }
 
+#[test]
+fn test_snapped_to_scene_change() {
+    let spec = ExtractionSpec::Image { time: 5.0 };
+    let period = Period::new(0.0, 10.0).unwrap();
+
+    // Snaps to whichever in-range scene change is closest to `time`.
+    let snapped = spec.snapped_to_scene_change(&[1.0, 5.4, 8.0], period);
+    match snapped {
+        ExtractionSpec::Image { time } => assert_eq!(5.4, time),
+        _ => panic!("expected an Image spec"),
+    }
+
+    // Scene changes outside `period` are ignored, even if they're closer
+    // to `time` than anything inside it.
+    let snapped =
+        spec.snapped_to_scene_change(&[4.9, 8.0], Period::new(5.0, 10.0).unwrap());
+    match snapped {
+        ExtractionSpec::Image { time } => assert_eq!(8.0, time),
+        _ => panic!("expected an Image spec"),
+    }
+
+    // No scene change in range at all: leave `time` untouched.
+    let snapped = spec.snapped_to_scene_change(&[], period);
+    match snapped {
+        ExtractionSpec::Image { time } => assert_eq!(5.0, time),
+        _ => panic!("expected an Image spec"),
+    }
+
+    // Non-`Image` specs are never snapped.
+    let audio = ExtractionSpec::Audio {
+        stream: None,
+        period,
+        metadata: Id3Metadata::default(),
+    };
+    match audio.snapped_to_scene_change(&[5.0], period) {
+        ExtractionSpec::Audio { .. } => {}
+        _ => panic!("expected an Audio spec"),
+    }
+}
+
 /// Information about what kind of data we want to extract.
 #[derive(Clone)]
 pub struct Extraction {
@@ -294,16 +640,54 @@ pub struct Extraction {
 
 impl Extraction {
     /// Add the necessary args to `cmd` to perform this extraction.
-    fn add_args(&self, cmd: &mut Command, time_base: f32) {
-        self.spec.add_args(cmd, time_base);
+    fn add_args(&self, cmd: &mut Command, video: &Video, time_base: f32) {
+        self.spec.add_args(cmd, video, time_base);
         cmd.arg(self.path.clone());
     }
 }
 
+/// Container-level metadata for a whole file, as opposed to metadata about
+/// an individual stream within it.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(missing_docs)]
+pub struct Format {
+    pub format_name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub duration: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub bit_rate: Option<u64>,
+    pub tags: Option<BTreeMap<String, String>>,
+}
+
+impl Format {
+    /// Parse this file's `creation_time` tag, if it has one.
+    fn creation_time(&self) -> Option<DateTime<Utc>> {
+        self.tags
+            .as_ref()?
+            .get("creation_time")?
+            .parse::<DateTime<Utc>>()
+            .ok()
+    }
+}
+
 /// Metadata associated with a video.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 struct Metadata {
     streams: Vec<Stream>,
+    format: Option<Format>,
+}
+
+/// Which backend to use when reading a video's container/stream metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ProbeBackend {
+    /// Shell out to `ffprobe`, the default. Falls back automatically to
+    /// [`ProbeBackend::Native`] if `ffprobe` can't be found on `PATH`.
+    #[default]
+    Ffprobe,
+    /// Use our built-in, pure-Rust ISO-BMFF reader, which avoids a
+    /// subprocess entirely but only understands the common MP4/M4A/MOV
+    /// container family.
+    Native,
 }
 
 /// Represents a video file on disk.
@@ -311,36 +695,133 @@ struct Metadata {
 pub struct Video {
     path: PathBuf,
     metadata: Metadata,
+    hw_accel: HwAccel,
 }
 
 impl Video {
     /// Create a new video file, given a path.
     pub async fn new(path: &Path) -> Result<Video> {
+        Video::new_with_backend(path, ProbeBackend::default()).await
+    }
+
+    /// Create a new video file, given a path, using the specified
+    /// [`ProbeBackend`] to read its stream metadata.
+    pub async fn new_with_backend(path: &Path, backend: ProbeBackend) -> Result<Video> {
         // Ensure we have an actual file before doing anything else.
         if !path.is_file() {
             return Err(anyhow!("No such file {:?}", path.display()));
         }
 
-        // Run our probe command.
+        let mut metadata = match backend {
+            ProbeBackend::Native => Metadata {
+                streams: iso_bmff::probe_streams(path)?,
+                format: iso_bmff::probe_format(path)?,
+            },
+            ProbeBackend::Ffprobe => match Video::ffprobe_metadata(path).await {
+                Ok(metadata) => metadata,
+                Err(e)
+                    if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                        == Some(io::ErrorKind::NotFound) =>
+                {
+                    debug!(
+                        "ffprobe not found on PATH, falling back to built-in ISO-BMFF reader"
+                    );
+                    Metadata {
+                        streams: iso_bmff::probe_streams(path)?,
+                        format: iso_bmff::probe_format(path)?,
+                    }
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        // `ffprobe` doesn't surface edit lists, and our native reader only
+        // populates them when it's also the one enumerating streams, so
+        // fill them in here when `backend` is `Ffprobe`. This is best-effort:
+        // a file that isn't ISO-BMFF at all (e.g. Matroska) just keeps
+        // every stream's `edit_list` as `None`. We match tracks up by
+        // `track_ID` (via each stream's `id` field) rather than position,
+        // since `ffprobe` may skip or reorder `trak`s relative to our own
+        // enumeration; a stream we can't match (no `id`, or no `trak`
+        // with that `track_ID`) simply keeps `edit_list: None`.
+        if backend == ProbeBackend::Ffprobe {
+            match iso_bmff::probe_edit_lists(path) {
+                Ok(edit_lists) => {
+                    for stream in &mut metadata.streams {
+                        let Some(track_id) = stream.id.as_deref().and_then(parse_hex_track_id)
+                        else {
+                            continue;
+                        };
+                        if let Some((_, edit_list)) =
+                            edit_lists.iter().find(|entry| entry.0 == track_id)
+                        {
+                            stream.edit_list = edit_list.clone();
+                        }
+                    }
+                }
+                Err(e) => debug!("could not read edit lists from {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(Video {
+            path: path.to_owned(),
+            metadata,
+            hw_accel: HwAccel::default(),
+        })
+    }
+
+    /// Run `ffprobe` and parse its output into our metadata.
+    async fn ffprobe_metadata(path: &Path) -> Result<Metadata> {
         let mkerr = || RunCommandError::new("ffprobe");
-        let cmd = Command::new("ffprobe")
+        let output = Command::new("ffprobe")
             .arg("-v")
             .arg("quiet")
             .arg("-show_streams")
+            .arg("-show_format")
             .arg("-of")
             .arg("json")
             .arg(path)
             .output()
-            .await;
-        let output = cmd.with_context(mkerr)?;
+            .await?;
         let stdout = from_utf8(&output.stdout).with_context(mkerr)?;
         debug!("Video metadata: {}", stdout);
-        let metadata = serde_json::from_str(stdout).with_context(mkerr)?;
+        serde_json::from_str(stdout).with_context(mkerr)
+    }
 
-        Ok(Video {
-            path: path.to_owned(),
-            metadata: metadata,
-        })
+    /// The duration of this file, in seconds, if known.
+    pub fn duration(&self) -> Option<f32> {
+        self.metadata.format.as_ref()?.duration
+    }
+
+    /// When this file was created, if its container recorded a
+    /// `creation_time` tag.
+    pub fn creation_time(&self) -> Option<DateTime<Utc>> {
+        self.metadata.format.as_ref()?.creation_time()
+    }
+
+    /// Guess [`Id3Metadata`] for this video from the tags already present
+    /// in its container (e.g. `artist`, `album`, `title`), as a starting
+    /// point before the caller overrides individual fields.
+    pub fn id3_metadata(&self) -> Id3Metadata {
+        let tags = self.metadata.format.as_ref().and_then(|f| f.tags.as_ref());
+        let tag = |key: &str| tags.and_then(|t| t.get(key)).cloned();
+        Id3Metadata {
+            genre: tag("genre"),
+            artist: tag("artist"),
+            album: tag("album"),
+            track_number: tag("track").as_deref().and_then(parse_track_number),
+            track_name: tag("title"),
+            lyrics: tag("lyrics"),
+        }
+    }
+
+    /// Use the specified hardware-accelerated decode backend for any
+    /// subsequent extractions or audio streaming performed on this video.
+    /// If the hardware decoder fails to initialize, extraction falls back
+    /// to software decoding automatically.
+    pub fn with_hw_accel(mut self, hw_accel: HwAccel) -> Video {
+        self.hw_accel = hw_accel;
+        self
     }
 
     /// Get just the file name of this video file.
@@ -376,21 +857,106 @@ impl Video {
         }
     }
 
-    /// Choose the best audio for the specified language.
-    pub fn audio_track_for(&self, lang: Lang) -> Option<StreamId> {
+    /// Find a stream of the given type whose language matches `lang`
+    /// exactly.
+    fn track_for(&self, codec_type: CodecType, lang: Lang) -> Option<StreamId> {
         self.streams()
             .iter()
-            .position(|s| {
-                s.codec_type == CodecType::Audio && s.language() == Some(lang)
+            .position(|s| s.codec_type == codec_type && s.language() == Some(lang))
+            .map(StreamId)
+    }
+
+    /// Of the streams of the given type, choose the one that best matches
+    /// `prefs`, a list of languages in priority order. If none of `prefs`
+    /// match, fall back to the stream flagged `default`. For subtitles,
+    /// next try the stream flagged `forced` (subtitles are sometimes
+    /// marked "forced" to show only foreign-language dialogue); that
+    /// disposition doesn't carry the same meaning for other codec types,
+    /// so it's skipped for them. Finally, fall back to the first stream of
+    /// that type.
+    fn best_track(&self, codec_type: CodecType, prefs: &[Lang]) -> Option<StreamId> {
+        prefs
+            .iter()
+            .find_map(|&lang| self.track_for(codec_type.clone(), lang))
+            .or_else(|| self.track_with_disposition(codec_type.clone(), "default"))
+            .or_else(|| {
+                // "forced" only means something for subtitles (foreign-
+                // dialogue-only tracks); an audio stream flagged "forced"
+                // isn't a better default than any other audio stream, so
+                // don't let it jump the queue there.
+                if codec_type == CodecType::Subtitle {
+                    self.track_with_disposition(codec_type.clone(), "forced")
+                } else {
+                    None
+                }
+            })
+            .or_else(|| {
+                self.streams()
+                    .iter()
+                    .position(|s| s.codec_type == codec_type)
+                    .map(StreamId)
             })
+    }
+
+    /// Find the first stream of the given type with `disposition` set.
+    fn track_with_disposition(&self, codec_type: CodecType, disposition: &str) -> Option<StreamId> {
+        self.streams()
+            .iter()
+            .position(|s| s.codec_type == codec_type && s.has_disposition(disposition))
             .map(StreamId)
     }
 
+    /// Choose the best audio for the specified language.
+    pub fn audio_track_for(&self, lang: Lang) -> Option<StreamId> {
+        self.track_for(CodecType::Audio, lang)
+    }
+
+    /// Choose the best audio track given a list of preferred languages, in
+    /// priority order. Falls back to the `default`-dispositioned audio
+    /// stream, and then the first audio stream, if none of `prefs` match.
+    pub fn best_audio_track(&self, prefs: &[Lang]) -> Option<StreamId> {
+        self.best_track(CodecType::Audio, prefs)
+    }
+
+    /// Choose the best subtitle track for the specified language.
+    pub fn subtitle_track_for(&self, lang: Lang) -> Option<StreamId> {
+        self.track_for(CodecType::Subtitle, lang)
+    }
+
+    /// Choose the best subtitle track given a list of preferred languages,
+    /// in priority order. Falls back to the `default`-dispositioned
+    /// subtitle stream, then the `forced`-dispositioned one, and then the
+    /// first subtitle stream, if none of `prefs` match.
+    pub fn best_subtitle_track(&self, prefs: &[Lang]) -> Option<StreamId> {
+        self.best_track(CodecType::Subtitle, prefs)
+    }
+
+    /// Map a time on the presentation timeline (the one subtitle
+    /// timestamps are aligned to) to the corresponding time on `stream`'s
+    /// underlying media timeline, following its edit list if it has one.
+    /// `stream` defaults to the first audio stream if `None`. Returns
+    /// `presentation_time` unchanged if we can't resolve a stream, or if
+    /// that stream has no edit list.
+    fn media_time_for(&self, stream: Option<StreamId>, presentation_time: f32) -> f32 {
+        let stream = stream
+            .and_then(|sid| self.streams().get(sid.0))
+            .or_else(|| self.streams().iter().find(|s| s.codec_type == CodecType::Audio));
+        match stream {
+            Some(stream) => stream.media_time_for_presentation_time(presentation_time),
+            None => presentation_time,
+        }
+    }
+
     /// Create an extraction command using the specified `time_base`.  This
     /// allows us to start extractions at any arbitrary point in the video
-    /// rapidly.
-    fn extract_command(&self, time_base: f32) -> Command {
+    /// rapidly. `hw_accel` overrides `self.hw_accel`, so that we can retry
+    /// with software decoding if the hardware decoder fails to initialize.
+    fn extract_command(&self, time_base: f32, hw_accel: HwAccel) -> Command {
         let mut cmd = Command::new("ffmpeg");
+        // Kill any in-flight ffmpeg process if we drop this future early,
+        // e.g. because a sibling extraction running concurrently failed.
+        cmd.kill_on_drop(true);
+        hw_accel.add_args(&mut cmd);
         cmd.arg("-ss").arg(format!("{}", time_base));
         cmd.arg("-i").arg(&self.path);
         cmd
@@ -399,11 +965,30 @@ impl Video {
     /// Perform a single extraction.
     async fn extract_one(&self, extraction: &Extraction) -> Result<()> {
         let time_base = extraction.spec.earliest_time();
-        let mut cmd = self.extract_command(time_base);
-        extraction.add_args(&mut cmd, time_base);
-        cmd.output()
+        let mut cmd = self.extract_command(time_base, self.hw_accel);
+        extraction.add_args(&mut cmd, self, time_base);
+        let output = cmd
+            .output()
             .await
             .with_context(|| RunCommandError::new("ffmpg"))?;
+        if !output.status.success() {
+            if self.hw_accel == HwAccel::None {
+                return Err(RunCommandError::new("ffmpg").into());
+            }
+            debug!(
+                "hardware-accelerated decode ({:?}) failed, falling back to software decode",
+                self.hw_accel
+            );
+            let mut cmd = self.extract_command(time_base, HwAccel::None);
+            extraction.add_args(&mut cmd, self, time_base);
+            let output = cmd
+                .output()
+                .await
+                .with_context(|| RunCommandError::new("ffmpg"))?;
+            if !output.status.success() {
+                return Err(RunCommandError::new("ffmpg").into());
+            }
+        }
         Ok(())
     }
 
@@ -417,20 +1002,60 @@ impl Video {
         let time_base = extractions[0].spec.earliest_time();
 
         // Build and run our batch extraction command.
-        let mut cmd = self.extract_command(time_base);
+        let mut cmd = self.extract_command(time_base, self.hw_accel);
         for e in extractions {
             assert!(e.spec.can_be_batched());
-            e.add_args(&mut cmd, time_base);
+            e.add_args(&mut cmd, self, time_base);
         }
-        cmd.output()
+        let output = cmd
+            .output()
             .await
             .with_context(|| RunCommandError::new("ffmpg"))?;
+        if !output.status.success() {
+            if self.hw_accel == HwAccel::None {
+                return Err(RunCommandError::new("ffmpg").into());
+            }
+            debug!(
+                "hardware-accelerated decode ({:?}) failed, falling back to software decode",
+                self.hw_accel
+            );
+            let mut cmd = self.extract_command(time_base, HwAccel::None);
+            for e in extractions {
+                e.add_args(&mut cmd, self, time_base);
+            }
+            let output = cmd
+                .output()
+                .await
+                .with_context(|| RunCommandError::new("ffmpg"))?;
+            if !output.status.success() {
+                return Err(RunCommandError::new("ffmpg").into());
+            }
+        }
         Ok(())
     }
 
+    /// How many ffmpeg extractions we should run at once.  Defaults to the
+    /// number of available CPU cores, but can be overridden with the
+    /// `SUBSTUDY_EXTRACT_JOBS` environment variable, which is mostly useful
+    /// for testing or for constraining extraction on a shared machine.
+    fn extraction_parallelism() -> usize {
+        if let Some(n) = env::var("SUBSTUDY_EXTRACT_JOBS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&n| n > 0)
+        {
+            return n;
+        }
+        available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+
     /// Perform a list of extractions as efficiently as possible.  We use a
-    /// batch interface to avoid making too many passes through the file.
-    /// We assume that the extractions are sorted in temporal order.
+    /// batch interface to avoid making too many passes through the file,
+    /// and we run independent batches and single-image extractions
+    /// concurrently (bounded by [`Video::extraction_parallelism`]) so that
+    /// a large study deck doesn't serialize every ffmpeg invocation onto a
+    /// single core.  We assume that the extractions are sorted in temporal
+    /// order, but that order is only required within a single batch.
     pub async fn extract(&self, ui: &Ui, extractions: &[Extraction]) -> Result<()> {
         let prog_conf = ProgressConfig {
             emoji: "✂️",
@@ -439,24 +1064,104 @@ impl Video {
         };
         let pb = ui.new_progress_bar(&prog_conf, cast::u64(extractions.len()));
 
+        // Build one job per non-batchable extraction, and one job per
+        // batch chunk.  Each job resolves to the number of extractions it
+        // completed, so we can keep the progress bar accurate.
         let mut batch: Vec<&Extraction> = vec![];
+        let mut jobs: Vec<Pin<Box<dyn Future<Output = Result<usize>> + '_>>> = vec![];
         for e in extractions {
             if e.spec.can_be_batched() {
                 batch.push(e);
             } else {
-                self.extract_one(e).await?;
-                pb.inc(1);
+                jobs.push(Box::pin(async move {
+                    self.extract_one(e).await?;
+                    Ok(1)
+                }));
             }
         }
-
         for chunk in batch.chunks(20) {
-            self.extract_batch(chunk).await?;
-            pb.inc(cast::u64(chunk.len()));
+            jobs.push(Box::pin(async move {
+                self.extract_batch(chunk).await?;
+                Ok(chunk.len())
+            }));
+        }
+
+        // Run up to `extraction_parallelism()` jobs at once.  We keep
+        // `FuturesUnordered` topped up by hand rather than using
+        // `buffer_unordered` so that we can bail out on the first error
+        // (dropping the remaining, not-yet-started jobs and killing any
+        // in-flight ffmpeg processes via `kill_on_drop`) instead of
+        // waiting for every job we already launched to finish.
+        let parallelism = Self::extraction_parallelism();
+        let mut remaining = jobs.into_iter();
+        let mut in_flight = FuturesUnordered::new();
+        for job in remaining.by_ref().take(parallelism) {
+            in_flight.push(job);
         }
+        while let Some(result) = in_flight.next().await {
+            pb.inc(cast::u64(result?));
+            if let Some(job) = remaining.next() {
+                in_flight.push(job);
+            }
+        }
+
         ui.finish(&prog_conf, pb);
         Ok(())
     }
 
+    /// Detect scene-change (cut) timestamps in this video, restricted to
+    /// `within` if given. `threshold` is ffmpeg's scene-change sensitivity,
+    /// from `0.0` to `1.0` (ffmpeg's own filter default is around `0.4`);
+    /// higher values only report more dramatic cuts.
+    ///
+    /// Callers can use these timestamps to snap an
+    /// [`ExtractionSpec::Image`] to the nearest scene boundary within a
+    /// subtitle's span, rather than always grabbing whatever frame happens
+    /// to be on screen at the dialogue's start time.
+    pub async fn scene_changes(
+        &self,
+        threshold: f32,
+        within: Option<Period>,
+    ) -> Result<Vec<f32>> {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.kill_on_drop(true);
+        cmd.arg("-v").arg("info").arg("-nostats");
+        let time_base = within.map(|period| period.begin()).unwrap_or(0.0);
+        if let Some(period) = within {
+            cmd.arg("-ss").arg(format!("{}", period.begin()));
+            cmd.arg("-t").arg(format!("{}", period.duration()));
+        }
+        cmd.arg("-i").arg(&self.path);
+        cmd.arg("-filter:v")
+            .arg(format!("select='gt(scene\\,{})',metadata=print", threshold));
+        cmd.arg("-f").arg("null").arg("-");
+
+        let output = cmd
+            .output()
+            .await
+            .with_context(|| RunCommandError::new("ffmpg"))?;
+        if !output.status.success() {
+            return Err(RunCommandError::new("ffmpg").into());
+        }
+        let mkerr = || RunCommandError::new("ffmpg");
+        let stdout = from_utf8(&output.stdout).with_context(mkerr)?;
+        let stderr = from_utf8(&output.stderr).with_context(mkerr)?;
+
+        // `metadata=print` writes to stdout by default, but we scan
+        // stderr too in case `select`/`showinfo`-style logging to the
+        // console is in play instead.
+        let re = Regex::new(r"pts_time:\s*(?P<time>[0-9.]+)").unwrap();
+        let mut times: Vec<f32> = re
+            .captures_iter(stdout)
+            .chain(re.captures_iter(stderr))
+            .filter_map(|cap| cap["time"].parse::<f32>().ok())
+            .map(|t| t + time_base)
+            .collect();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times.dedup();
+        Ok(times)
+    }
+
     /// Get the attached picture from a "video" file. This typically happens
     /// when the video file is actually a music file with album art attached.
     /// Returns the file extension of the extracted image.
@@ -483,10 +1188,18 @@ impl Video {
     /// ```
     ///
     /// The stream will contain either 16-bit signed little-endian PCM or
-    /// big-endian PCM, depending on the target architecture.
+    /// big-endian PCM, depending on the target architecture. If `period`
+    /// is given, the returned stream only covers that span; `period` is
+    /// interpreted on the presentation timeline (the one subtitle
+    /// timestamps are aligned to) and mapped to `stream`'s underlying
+    /// media timeline via its edit list, the same way
+    /// [`ExtractionSpec::Audio`]'s period is, so extracted audio stays in
+    /// sync with subtitles even on files with encoder priming or other
+    /// edits.
     pub async fn open_audio_stream(
         &self,
         stream: Option<StreamId>,
+        period: Option<Period>,
         rate: usize,
     ) -> Result<(BufReader<impl AsyncRead>, impl Future<Output = Result<()>>)> {
         let encoding = if cfg!(target_endian = "big") {
@@ -497,10 +1210,19 @@ impl Video {
 
         let mut cmd = Command::new("ffmpeg");
         cmd.arg("-v").arg("quiet");
+        self.hw_accel.add_args(&mut cmd);
         cmd.arg("-i").arg(&self.path);
         if let Some(stream) = stream {
             cmd.arg("-map").arg(format!("0:{}", stream.0));
         }
+        if let Some(period) = period {
+            let media_begin = self.media_time_for(stream, period.begin());
+            let media_end = self.media_time_for(stream, period.end());
+            cmd.arg("-ss")
+                .arg(format!("{}", media_begin))
+                .arg("-t")
+                .arg(format!("{}", media_end - media_begin));
+        }
         cmd.arg("-acodec").arg(format!("pcm_{}", encoding));
         cmd.arg("-f").arg(encoding);
         cmd.arg("-ac").arg("1");
@@ -522,3 +1244,118 @@ impl Video {
         Ok((BufReader::new(stdout), join_handle))
     }
 }
+
+#[test]
+fn test_extraction_parallelism_env_override() {
+    // This lock is local to this test, so it only serializes concurrent
+    // runs of this test itself; no other test touches this env var.
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap();
+
+    let saved = env::var("SUBSTUDY_EXTRACT_JOBS").ok();
+
+    env::set_var("SUBSTUDY_EXTRACT_JOBS", "3");
+    assert_eq!(3, Video::extraction_parallelism());
+
+    // `0` isn't a valid job count, so fall back to the CPU-derived default
+    // instead of returning `0`.
+    env::set_var("SUBSTUDY_EXTRACT_JOBS", "0");
+    assert_eq!(
+        available_parallelism().map(|n| n.get()).unwrap_or(1),
+        Video::extraction_parallelism()
+    );
+
+    // Same for a value that isn't a number at all.
+    env::set_var("SUBSTUDY_EXTRACT_JOBS", "not-a-number");
+    assert_eq!(
+        available_parallelism().map(|n| n.get()).unwrap_or(1),
+        Video::extraction_parallelism()
+    );
+
+    env::remove_var("SUBSTUDY_EXTRACT_JOBS");
+    assert_eq!(
+        available_parallelism().map(|n| n.get()).unwrap_or(1),
+        Video::extraction_parallelism()
+    );
+
+    match saved {
+        Some(value) => env::set_var("SUBSTUDY_EXTRACT_JOBS", value),
+        None => env::remove_var("SUBSTUDY_EXTRACT_JOBS"),
+    }
+}
+
+#[test]
+fn test_best_track_fallback_chain() {
+    fn stream(codec_type: CodecType, lang: Option<&str>, disposition: Option<&str>) -> Stream {
+        Stream {
+            index: 0,
+            id: None,
+            codec_type,
+            codec_name: None,
+            sample_rate: None,
+            channels: None,
+            duration: None,
+            bit_rate: None,
+            tags: lang.map(|lang| {
+                let mut tags = BTreeMap::new();
+                tags.insert("language".to_owned(), lang.to_owned());
+                tags
+            }),
+            disposition: disposition.map(|flag| {
+                let mut disposition = BTreeMap::new();
+                disposition.insert(flag.to_owned(), 1);
+                disposition
+            }),
+            edit_list: None,
+        }
+    }
+
+    fn video(streams: Vec<Stream>) -> Video {
+        Video {
+            path: PathBuf::new(),
+            metadata: Metadata {
+                streams,
+                format: None,
+            },
+            hw_accel: HwAccel::default(),
+        }
+    }
+
+    let fra = Lang::iso639("fr").unwrap();
+    let eng = Lang::iso639("en").unwrap();
+
+    // A stream whose language matches `prefs` wins outright, even over a
+    // `default`-flagged stream earlier in the list.
+    let v = video(vec![
+        stream(CodecType::Audio, Some("eng"), Some("default")),
+        stream(CodecType::Audio, Some("fra"), None),
+    ]);
+    assert_eq!(Some(StreamId(1)), v.best_audio_track(&[fra]));
+
+    // No language match: fall back to the `default`-flagged stream.
+    let v = video(vec![
+        stream(CodecType::Audio, Some("eng"), None),
+        stream(CodecType::Audio, Some("fra"), Some("default")),
+    ]);
+    assert_eq!(Some(StreamId(1)), v.best_audio_track(&[]));
+
+    // For subtitles, a `forced` stream is used when nothing matches and
+    // there's no `default` stream either.
+    let v = video(vec![
+        stream(CodecType::Subtitle, Some("eng"), None),
+        stream(CodecType::Subtitle, Some("fra"), Some("forced")),
+    ]);
+    assert_eq!(Some(StreamId(1)), v.best_subtitle_track(&[]));
+
+    // For audio, a `forced` disposition is not treated specially; we fall
+    // straight through to the first stream of that type instead.
+    let v = video(vec![
+        stream(CodecType::Audio, Some("eng"), None),
+        stream(CodecType::Audio, Some("fra"), Some("forced")),
+    ]);
+    assert_eq!(Some(StreamId(0)), v.best_audio_track(&[]));
+
+    // Nothing matches at all: fall back to the first stream of that type.
+    let v = video(vec![stream(CodecType::Audio, Some("fra"), None)]);
+    assert_eq!(Some(StreamId(0)), v.best_audio_track(&[eng]));
+}
@@ -0,0 +1,692 @@
+//! A minimal, pure-Rust reader for the ISO base media file format (the
+//! "box"-based container used by MP4, M4A and MOV). This lets us list the
+//! streams in a file without shelling out to `ffprobe`, which is handy on
+//! systems that ship only a partial `ffmpeg` install, or not one at all.
+//!
+//! We only look for the handful of boxes we actually need
+//! (`moov`/`mvhd`/`trak`/`tkhd`/`edts`/`elst`/`mdia`/`hdlr`/`mdhd`/`elng`/
+//! `minf`/`stbl`/`stsd`), and we
+//! seek past everything else (most importantly `mdat`, which holds the
+//! actual audio/video samples and can be almost the entire file).
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context as _};
+
+use super::{CodecType, EditListEntry, Format, Stream};
+use crate::Result;
+
+/// A parsed box header: its 4-character type code, plus the byte range
+/// (relative to the start of the file) of its payload.
+struct BoxHeader {
+    box_type: [u8; 4],
+    payload_start: u64,
+    payload_end: u64,
+}
+
+impl BoxHeader {
+    fn is_type(&self, box_type: &[u8; 4]) -> bool {
+        &self.box_type == box_type
+    }
+}
+
+/// Read a single box header starting at the reader's current position.
+/// `container_end` resolves the legacy "box extends to the end of its
+/// enclosing box" convention (a 32-bit size of 0). Returns `None` once
+/// we've reached `container_end`.
+fn read_box_header(
+    r: &mut (impl Read + Seek),
+    container_end: u64,
+) -> Result<Option<BoxHeader>> {
+    let start = r.stream_position()?;
+    if start >= container_end {
+        return Ok(None);
+    }
+
+    let mut size_buf = [0u8; 4];
+    match r.read_exact(&mut size_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut box_type = [0u8; 4];
+    r.read_exact(&mut box_type)?;
+
+    let small_size = u32::from_be_bytes(size_buf) as u64;
+    let (size, header_len) = if small_size == 1 {
+        let mut large = [0u8; 8];
+        r.read_exact(&mut large)?;
+        (u64::from_be_bytes(large), 16)
+    } else if small_size == 0 {
+        (container_end - start, 8)
+    } else {
+        (small_size, 8)
+    };
+
+    let payload_start = start + header_len;
+    let payload_end = start + size;
+    // A well-formed box's size must be at least as large as its own
+    // header, i.e. `payload_end` can never land before `payload_start`
+    // (an empty-bodied box has them equal). A box that claims a smaller
+    // size than that -- most notably a 32-bit size of `1` (use the
+    // 64-bit size) paired with a 64-bit size of `0` -- would otherwise
+    // seek back to the exact offset we just read and loop here forever;
+    // reject it instead, the way `ffprobe` would reject a corrupt
+    // container.
+    if payload_end < payload_start {
+        return Err(anyhow!(
+            "malformed '{}' box at offset {}: size {} is smaller than its own header",
+            String::from_utf8_lossy(&box_type),
+            start,
+            size
+        ));
+    }
+
+    Ok(Some(BoxHeader {
+        box_type,
+        payload_start,
+        payload_end,
+    }))
+}
+
+/// List the child boxes of the box (or file) whose payload spans
+/// `start..end`, without descending into grandchildren.
+fn child_boxes(r: &mut (impl Read + Seek), start: u64, end: u64) -> Result<Vec<BoxHeader>> {
+    let mut boxes = vec![];
+    r.seek(SeekFrom::Start(start))?;
+    while let Some(header) = read_box_header(r, end)? {
+        let next = header.payload_end;
+        boxes.push(header);
+        r.seek(SeekFrom::Start(next))?;
+    }
+    Ok(boxes)
+}
+
+fn find_box<'a>(boxes: &'a [BoxHeader], box_type: &[u8; 4]) -> Option<&'a BoxHeader> {
+    boxes.iter().find(|b| b.is_type(box_type))
+}
+
+/// Read a box's payload into memory. Only used for boxes we know are tiny
+/// (`mvhd`, `tkhd`, `elst`, `hdlr`, `mdhd`, `elng`, `stsd`); we never do
+/// this for `mdat`.
+fn read_payload(r: &mut (impl Read + Seek), header: &BoxHeader) -> Result<Vec<u8>> {
+    let len = header.payload_end.saturating_sub(header.payload_start);
+    r.seek(SeekFrom::Start(header.payload_start))?;
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Decode an ISO 639-2/T language code packed into a `mdhd` box's 16-bit
+/// `language` field as three 5-bit values, each offset by `0x60`.
+fn decode_packed_language(packed: u16) -> Option<String> {
+    if packed == 0 {
+        return None;
+    }
+    let c1 = ((packed >> 10) & 0x1f) as u8 + 0x60;
+    let c2 = ((packed >> 5) & 0x1f) as u8 + 0x60;
+    let c3 = (packed & 0x1f) as u8 + 0x60;
+    let lang: String = [c1, c2, c3].iter().map(|&b| b as char).collect();
+    if lang == "und" {
+        None
+    } else {
+        Some(lang)
+    }
+}
+
+/// Parse a `hdlr` box's payload and return its 4-character handler type
+/// (e.g. `"vide"`, `"soun"`, `"sbtl"`).
+fn parse_hdlr(payload: &[u8]) -> Result<[u8; 4]> {
+    // version(1) + flags(3) + pre_defined(4) + handler_type(4) + ...
+    if payload.len() < 12 {
+        return Err(anyhow!("hdlr box too short"));
+    }
+    let mut handler_type = [0u8; 4];
+    handler_type.copy_from_slice(&payload[8..12]);
+    Ok(handler_type)
+}
+
+/// Parse a `tkhd` box's payload and return the track's `track_ID`, the
+/// same identifier `ffprobe` reports (in hex) as a stream's `id` field.
+fn parse_tkhd(payload: &[u8]) -> Result<u32> {
+    if payload.is_empty() {
+        return Err(anyhow!("tkhd box too short"));
+    }
+    let version = payload[0];
+    // version(1) + flags(3), then either 32- or 64-bit
+    // creation_time/modification_time, then a 32-bit track_ID.
+    let time_len = if version == 1 { 8 } else { 4 };
+    let track_id_offset = 4 + time_len * 2;
+    if payload.len() < track_id_offset + 4 {
+        return Err(anyhow!("tkhd box too short"));
+    }
+    Ok(u32::from_be_bytes(
+        payload[track_id_offset..track_id_offset + 4].try_into()?,
+    ))
+}
+
+/// Parse a `mdhd` box's payload and return `(duration, timescale, packed
+/// language)`. The track's duration in seconds is `duration / timescale`.
+fn parse_mdhd(payload: &[u8]) -> Result<(u64, u32, u16)> {
+    if payload.is_empty() {
+        return Err(anyhow!("mdhd box too short"));
+    }
+    let version = payload[0];
+    // version(1) + flags(3), then either 32- or 64-bit
+    // creation_time/modification_time/timescale/duration, then a 16-bit
+    // packed language code.
+    let (timescale_offset, duration_len) =
+        if version == 1 { (4 + 8 + 8, 8) } else { (4 + 4 + 4, 4) };
+    let lang_offset = timescale_offset + 4 + duration_len;
+    if payload.len() < lang_offset + 2 {
+        return Err(anyhow!("mdhd box too short"));
+    }
+    let timescale =
+        u32::from_be_bytes(payload[timescale_offset..timescale_offset + 4].try_into()?);
+    let duration_offset = timescale_offset + 4;
+    let duration = if duration_len == 8 {
+        u64::from_be_bytes(payload[duration_offset..duration_offset + 8].try_into()?)
+    } else {
+        u32::from_be_bytes(payload[duration_offset..duration_offset + 4].try_into()?) as u64
+    };
+    let language = u16::from_be_bytes([payload[lang_offset], payload[lang_offset + 1]]);
+    Ok((duration, timescale, language))
+}
+
+/// Parse a `mvhd` box's payload and return the movie's overall duration,
+/// in seconds.
+fn parse_mvhd_duration(payload: &[u8]) -> Option<f32> {
+    if payload.is_empty() {
+        return None;
+    }
+    let version = payload[0];
+    // version(1) + flags(3), then either 32- or 64-bit
+    // creation_time/modification_time/timescale/duration.
+    let (timescale_offset, duration_len) = if version == 1 { (4 + 8 + 8, 8) } else { (4 + 4 + 4, 4) };
+    if payload.len() < timescale_offset + 4 + duration_len {
+        return None;
+    }
+    let timescale = u32::from_be_bytes(
+        payload[timescale_offset..timescale_offset + 4].try_into().ok()?,
+    );
+    let duration_offset = timescale_offset + 4;
+    let duration = if duration_len == 8 {
+        u64::from_be_bytes(payload[duration_offset..duration_offset + 8].try_into().ok()?)
+    } else {
+        u32::from_be_bytes(payload[duration_offset..duration_offset + 4].try_into().ok()?) as u64
+    };
+    if timescale == 0 {
+        None
+    } else {
+        Some(duration as f32 / timescale as f32)
+    }
+}
+
+/// Parse a `mvhd` box's payload and return the movie's overall timescale
+/// (units per second), used to interpret `elst` segment durations, which
+/// are expressed on the movie timeline rather than the track's own.
+fn parse_mvhd_timescale(payload: &[u8]) -> Option<u32> {
+    if payload.is_empty() {
+        return None;
+    }
+    let version = payload[0];
+    let timescale_offset = if version == 1 { 4 + 8 + 8 } else { 4 + 4 + 4 };
+    if payload.len() < timescale_offset + 4 {
+        return None;
+    }
+    Some(u32::from_be_bytes(
+        payload[timescale_offset..timescale_offset + 4].try_into().ok()?,
+    ))
+}
+
+/// Parse an `elst` (edit list) box's payload into resolved
+/// [`EditListEntry`] values. `segment_duration` is expressed on the movie
+/// timeline (`movie_timescale`), while `media_time` is expressed on the
+/// track's own media timeline (`media_timescale`).
+fn parse_elst(
+    payload: &[u8],
+    movie_timescale: u32,
+    media_timescale: u32,
+) -> Option<Vec<EditListEntry>> {
+    if payload.is_empty() || movie_timescale == 0 || media_timescale == 0 {
+        return None;
+    }
+    let version = payload[0];
+    let entry_size = if version == 1 { 20 } else { 12 };
+    // version(1) + flags(3) + entry_count(4)
+    if payload.len() < 8 {
+        return None;
+    }
+    let entry_count = u32::from_be_bytes(payload[4..8].try_into().ok()?) as usize;
+
+    // `entry_count` comes straight from the file and is untrusted, so don't
+    // let a bogus value (e.g. `u32::MAX`) drive an oversized upfront
+    // allocation; cap it at the number of entries the payload could
+    // actually hold, matching the per-entry length check in the loop below.
+    let max_entries = (payload.len() - 8) / entry_size;
+    let mut entries = Vec::with_capacity(entry_count.min(max_entries));
+    let mut offset = 8;
+    let mut presentation_start = 0.0f32;
+    for _ in 0..entry_count {
+        if payload.len() < offset + entry_size {
+            break;
+        }
+        let (segment_duration, media_time) = if version == 1 {
+            let segment_duration = u64::from_be_bytes(payload[offset..offset + 8].try_into().ok()?);
+            let media_time = i64::from_be_bytes(payload[offset + 8..offset + 16].try_into().ok()?);
+            (segment_duration, media_time)
+        } else {
+            let segment_duration =
+                u32::from_be_bytes(payload[offset..offset + 4].try_into().ok()?) as u64;
+            let media_time =
+                i32::from_be_bytes(payload[offset + 4..offset + 8].try_into().ok()?) as i64;
+            (segment_duration, media_time)
+        };
+        let rate_offset = offset + entry_size - 4;
+        let rate_integer = i16::from_be_bytes(payload[rate_offset..rate_offset + 2].try_into().ok()?);
+        let rate_fraction =
+            u16::from_be_bytes(payload[rate_offset + 2..rate_offset + 4].try_into().ok()?);
+        offset += entry_size;
+
+        let duration = segment_duration as f32 / movie_timescale as f32;
+        // A `media_time` of -1 marks an "empty edit" (e.g. a segment of
+        // inserted silence with no backing media); there's no media
+        // position to report, so we just hold at the previous one.
+        let media_start = if media_time < 0 {
+            entries.last().map_or(0.0, |e: &EditListEntry| e.media_start)
+        } else {
+            media_time as f32 / media_timescale as f32
+        };
+        entries.push(EditListEntry {
+            presentation_start,
+            duration,
+            media_start,
+            media_rate: rate_integer as f32 + rate_fraction as f32 / 65536.0,
+        });
+        presentation_start += duration;
+    }
+
+    if entries.is_empty() {
+        None
+    } else {
+        Some(entries)
+    }
+}
+
+/// Read a track's edit list (its `edts/elst` box), if it has one.
+fn read_edit_list(
+    file: &mut File,
+    trak_children: &[BoxHeader],
+    movie_timescale: Option<u32>,
+    media_timescale: u32,
+) -> Result<Option<Vec<EditListEntry>>> {
+    let Some(movie_timescale) = movie_timescale else {
+        return Ok(None);
+    };
+    let Some(edts) = find_box(trak_children, b"edts") else {
+        return Ok(None);
+    };
+    let edts_children = child_boxes(file, edts.payload_start, edts.payload_end)?;
+    let Some(elst) = find_box(&edts_children, b"elst") else {
+        return Ok(None);
+    };
+    let payload = read_payload(file, elst)?;
+    Ok(parse_elst(&payload, movie_timescale, media_timescale))
+}
+
+/// Parse an `elng` (extended language tag) box's payload, a BCP-47 tag
+/// string following the usual 4-byte full-box header.
+fn parse_elng(payload: &[u8]) -> Option<String> {
+    let tag = payload.get(4..)?;
+    let end = tag.iter().position(|&b| b == 0).unwrap_or(tag.len());
+    std::str::from_utf8(&tag[..end]).ok().map(str::to_owned)
+}
+
+/// Does this track's `stsd` box describe an image codec (`jpeg`, `png`,
+/// `bmp`)? Image-coded video tracks are how MP4/M4A files embed attached
+/// pictures such as album art.
+fn stsd_is_image_codec(payload: &[u8]) -> bool {
+    matches!(stsd_sample_format(payload).as_deref(), Some("jpeg" | "png " | "bmp "))
+}
+
+/// Read the 4-character sample format of a `stsd` box's first sample
+/// entry, e.g. `"avc1"`, `"mp4a"` or `"jpeg"`. This is a rough stand-in
+/// for ffprobe's `codec_name`; it's the container-level format tag rather
+/// than a normalized codec name.
+fn stsd_sample_format(payload: &[u8]) -> Option<String> {
+    // full box header(4) + entry_count(4) + first entry: size(4) + format(4)
+    if payload.len() < 16 {
+        return None;
+    }
+    std::str::from_utf8(&payload[12..16]).ok().map(str::to_owned)
+}
+
+fn codec_type_for_handler(handler_type: &[u8; 4]) -> CodecType {
+    match handler_type {
+        b"vide" => CodecType::Video,
+        b"soun" => CodecType::Audio,
+        b"sbtl" | b"text" | b"subt" => CodecType::Subtitle,
+        other => CodecType::Other(String::from_utf8_lossy(other).into_owned()),
+    }
+}
+
+/// Open `path` and return a reader positioned at the start of the file,
+/// along with the child boxes of its top-level `moov` box.
+fn open_moov(path: &Path) -> Result<(File, Vec<BoxHeader>)> {
+    let mut file = File::open(path)
+        .with_context(|| format!("could not open {}", path.display()))?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+
+    let top_level = child_boxes(&mut file, 0, file_len)?;
+    let moov = find_box(&top_level, b"moov").ok_or_else(|| {
+        anyhow!(
+            "no 'moov' box found in {}; not a supported ISO-BMFF file",
+            path.display()
+        )
+    })?;
+    let moov_children = child_boxes(&mut file, moov.payload_start, moov.payload_end)?;
+    Ok((file, moov_children))
+}
+
+/// List the streams in an MP4/M4A/MOV file by walking its box structure
+/// directly, without shelling out to `ffprobe`.
+pub(super) fn probe_streams(path: &Path) -> Result<Vec<Stream>> {
+    let (mut file, moov_children) = open_moov(path)?;
+    let movie_timescale = find_box(&moov_children, b"mvhd")
+        .and_then(|mvhd| read_payload(&mut file, mvhd).ok())
+        .and_then(|payload| parse_mvhd_timescale(&payload));
+
+    let mut streams = vec![];
+    for (index, trak) in moov_children.iter().filter(|b| b.is_type(b"trak")).enumerate() {
+        let trak_children = child_boxes(&mut file, trak.payload_start, trak.payload_end)?;
+        let track_id = find_box(&trak_children, b"tkhd")
+            .and_then(|tkhd| read_payload(&mut file, tkhd).ok())
+            .and_then(|payload| parse_tkhd(&payload).ok());
+        let Some(mdia) = find_box(&trak_children, b"mdia") else {
+            continue;
+        };
+        let mdia_children = child_boxes(&mut file, mdia.payload_start, mdia.payload_end)?;
+
+        let codec_type = match find_box(&mdia_children, b"hdlr") {
+            Some(hdlr) => {
+                let payload = read_payload(&mut file, hdlr)?;
+                codec_type_for_handler(&parse_hdlr(&payload)?)
+            }
+            None => continue,
+        };
+
+        let mut language = None;
+        let mut duration = None;
+        let mut media_timescale = 0;
+        if let Some(mdhd) = find_box(&mdia_children, b"mdhd") {
+            let payload = read_payload(&mut file, mdhd)?;
+            let (track_duration, timescale, packed_language) = parse_mdhd(&payload)?;
+            language = decode_packed_language(packed_language);
+            media_timescale = timescale;
+            if timescale > 0 {
+                duration = Some(track_duration as f32 / timescale as f32);
+            }
+        }
+        let edit_list = read_edit_list(&mut file, &trak_children, movie_timescale, media_timescale)?;
+        if let Some(elng) = find_box(&mdia_children, b"elng") {
+            let payload = read_payload(&mut file, elng)?;
+            if let Some(tag) = parse_elng(&payload) {
+                language = Some(tag);
+            }
+        }
+
+        let mut codec_name = None;
+        let mut is_attached_pic = false;
+        if let Some(minf) = find_box(&mdia_children, b"minf") {
+            let minf_children = child_boxes(&mut file, minf.payload_start, minf.payload_end)?;
+            if let Some(stbl) = find_box(&minf_children, b"stbl") {
+                let stbl_children =
+                    child_boxes(&mut file, stbl.payload_start, stbl.payload_end)?;
+                if let Some(stsd) = find_box(&stbl_children, b"stsd") {
+                    let payload = read_payload(&mut file, stsd)?;
+                    codec_name = stsd_sample_format(&payload);
+                    if codec_type == CodecType::Video {
+                        is_attached_pic = stsd_is_image_codec(&payload);
+                    }
+                }
+            }
+        }
+
+        let tags = language.map(|lang| {
+            let mut tags = std::collections::BTreeMap::new();
+            tags.insert("language".to_owned(), lang);
+            tags
+        });
+        let disposition = if is_attached_pic {
+            let mut disposition = std::collections::BTreeMap::new();
+            disposition.insert("attached_pic".to_owned(), 1);
+            Some(disposition)
+        } else {
+            None
+        };
+
+        streams.push(Stream {
+            index,
+            id: track_id.map(|id| format!("0x{:x}", id)),
+            codec_type,
+            codec_name,
+            // The native reader doesn't currently parse `stsd` sample
+            // entries deeply enough to recover sample rate, channel
+            // count, or bit rate; ffprobe remains the richer backend for
+            // those.
+            sample_rate: None,
+            channels: None,
+            duration,
+            bit_rate: None,
+            tags,
+            disposition,
+            edit_list,
+        });
+    }
+
+    Ok(streams)
+}
+
+/// Read each track's edit list, keyed by `track_ID` (the `tkhd` field that
+/// `ffprobe` also reports, in hex, as a stream's `id`), for use when
+/// stream metadata itself came from `ffprobe` (which doesn't surface edit
+/// lists). Keying by `track_ID` rather than `trak` position lets the
+/// caller match these back up to `ffprobe`'s own stream list even if the
+/// two enumerations disagree on order or on which tracks to include (e.g.
+/// a hint or meta track `ffprobe` omits).
+pub(super) fn probe_edit_lists(path: &Path) -> Result<Vec<(u32, Option<Vec<EditListEntry>>)>> {
+    let (mut file, moov_children) = open_moov(path)?;
+    let movie_timescale = find_box(&moov_children, b"mvhd")
+        .and_then(|mvhd| read_payload(&mut file, mvhd).ok())
+        .and_then(|payload| parse_mvhd_timescale(&payload));
+
+    let mut edit_lists = vec![];
+    for trak in moov_children.iter().filter(|b| b.is_type(b"trak")) {
+        let trak_children = child_boxes(&mut file, trak.payload_start, trak.payload_end)?;
+        let Some(track_id) = find_box(&trak_children, b"tkhd")
+            .and_then(|tkhd| read_payload(&mut file, tkhd).ok())
+            .and_then(|payload| parse_tkhd(&payload).ok())
+        else {
+            // No usable `track_ID`; we can't match this track back up to
+            // an `ffprobe` stream, so skip it rather than guess.
+            continue;
+        };
+        let media_timescale = find_box(&trak_children, b"mdia")
+            .and_then(|mdia| child_boxes(&mut file, mdia.payload_start, mdia.payload_end).ok())
+            .and_then(|mdia_children| {
+                let mdhd = find_box(&mdia_children, b"mdhd")?;
+                let payload = read_payload(&mut file, mdhd).ok()?;
+                let (_, timescale, _) = parse_mdhd(&payload).ok()?;
+                Some(timescale)
+            })
+            .unwrap_or(0);
+        let edit_list = read_edit_list(&mut file, &trak_children, movie_timescale, media_timescale)?;
+        edit_lists.push((track_id, edit_list));
+    }
+    Ok(edit_lists)
+}
+
+/// Read this file's overall duration via the `moov/mvhd` box. The native
+/// reader doesn't currently recover an overall bit rate or container tags,
+/// so the rest of [`Format`] is left empty.
+pub(super) fn probe_format(path: &Path) -> Result<Option<Format>> {
+    let (mut file, moov_children) = open_moov(path)?;
+    let Some(mvhd) = find_box(&moov_children, b"mvhd") else {
+        return Ok(None);
+    };
+    let payload = read_payload(&mut file, mvhd)?;
+    Ok(Some(Format {
+        duration: parse_mvhd_duration(&payload),
+        ..Format::default()
+    }))
+}
+
+#[test]
+fn test_read_box_header_rejects_truncated_large_size() {
+    // A 32-bit size of `1` means "use the 64-bit size that follows", and a
+    // 64-bit size of `0` is never valid (it doesn't even cover the header
+    // we just read). This is the exact byte pattern that used to make
+    // `child_boxes` seek back to its own start and loop forever.
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&1u32.to_be_bytes());
+    bytes.extend_from_slice(b"free");
+    bytes.extend_from_slice(&0u64.to_be_bytes());
+    let mut cursor = io::Cursor::new(bytes);
+    let container_end = cursor.get_ref().len() as u64;
+    assert!(read_box_header(&mut cursor, container_end).is_err());
+}
+
+#[test]
+fn test_read_box_header_allows_empty_box() {
+    // A box that's exactly as long as its own header (no payload) is
+    // legitimate, e.g. a bare `free` box, and must not be rejected.
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&8u32.to_be_bytes());
+    bytes.extend_from_slice(b"free");
+    let mut cursor = io::Cursor::new(bytes);
+    let container_end = cursor.get_ref().len() as u64;
+    let header = read_box_header(&mut cursor, container_end)
+        .unwrap()
+        .unwrap();
+    assert_eq!(header.payload_start, header.payload_end);
+}
+
+#[test]
+fn test_child_boxes_errors_instead_of_hanging_on_malformed_box() {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&1u32.to_be_bytes());
+    bytes.extend_from_slice(b"free");
+    bytes.extend_from_slice(&0u64.to_be_bytes());
+    let mut cursor = io::Cursor::new(bytes);
+    let end = cursor.get_ref().len() as u64;
+    assert!(child_boxes(&mut cursor, 0, end).is_err());
+}
+
+#[test]
+fn test_parse_mdhd_version0() {
+    let mut payload = vec![];
+    payload.extend_from_slice(&[0, 0, 0, 0]); // version(1) + flags(3)
+    payload.extend_from_slice(&0u32.to_be_bytes()); // creation_time
+    payload.extend_from_slice(&0u32.to_be_bytes()); // modification_time
+    payload.extend_from_slice(&1000u32.to_be_bytes()); // timescale
+    payload.extend_from_slice(&5000u32.to_be_bytes()); // duration
+    payload.extend_from_slice(&0x15c7u16.to_be_bytes()); // packed "eng"
+    payload.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+
+    let (duration, timescale, language) = parse_mdhd(&payload).unwrap();
+    assert_eq!(duration, 5000);
+    assert_eq!(timescale, 1000);
+    assert_eq!(decode_packed_language(language).as_deref(), Some("eng"));
+}
+
+#[test]
+fn test_parse_mdhd_version1() {
+    let mut payload = vec![];
+    payload.extend_from_slice(&[1, 0, 0, 0]); // version(1) + flags(3)
+    payload.extend_from_slice(&0u64.to_be_bytes()); // creation_time
+    payload.extend_from_slice(&0u64.to_be_bytes()); // modification_time
+    payload.extend_from_slice(&48000u32.to_be_bytes()); // timescale
+    payload.extend_from_slice(&96000u64.to_be_bytes()); // duration
+    payload.extend_from_slice(&0u16.to_be_bytes()); // packed language ("und")
+    payload.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+
+    let (duration, timescale, language) = parse_mdhd(&payload).unwrap();
+    assert_eq!(duration, 96000);
+    assert_eq!(timescale, 48000);
+    assert_eq!(decode_packed_language(language), None);
+}
+
+#[test]
+fn test_parse_tkhd_version0() {
+    let mut payload = vec![];
+    payload.extend_from_slice(&[0, 0, 0, 0]); // version(1) + flags(3)
+    payload.extend_from_slice(&0u32.to_be_bytes()); // creation_time
+    payload.extend_from_slice(&0u32.to_be_bytes()); // modification_time
+    payload.extend_from_slice(&7u32.to_be_bytes()); // track_ID
+    assert_eq!(parse_tkhd(&payload).unwrap(), 7);
+}
+
+#[test]
+fn test_parse_elst_version0_single_entry() {
+    let movie_timescale = 1000;
+    let media_timescale = 44100;
+    let mut payload = vec![];
+    payload.extend_from_slice(&[0, 0, 0, 0]); // version(1) + flags(3)
+    payload.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    payload.extend_from_slice(&500u32.to_be_bytes()); // segment_duration
+    payload.extend_from_slice(&2112i32.to_be_bytes()); // media_time
+    payload.extend_from_slice(&1i16.to_be_bytes()); // rate_integer
+    payload.extend_from_slice(&0u16.to_be_bytes()); // rate_fraction
+
+    let entries = parse_elst(&payload, movie_timescale, media_timescale).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].presentation_start, 0.0);
+    assert!((entries[0].duration - 0.5).abs() < 1e-6);
+    assert!((entries[0].media_start - 2112.0 / 44100.0).abs() < 1e-6);
+    assert_eq!(entries[0].media_rate, 1.0);
+}
+
+#[test]
+fn test_parse_elst_rejects_oversized_entry_count() {
+    // A corrupt/truncated `elst` box claiming far more entries than its
+    // payload could possibly hold (most dramatically `entry_count =
+    // u32::MAX`) must not drive an oversized upfront allocation -- it
+    // should just parse however many entries the payload actually has
+    // room for and stop there.
+    let movie_timescale = 1000;
+    let media_timescale = 44100;
+    let mut payload = vec![];
+    payload.extend_from_slice(&[0, 0, 0, 0]); // version(1) + flags(3)
+    payload.extend_from_slice(&u32::MAX.to_be_bytes()); // bogus entry_count
+    payload.extend_from_slice(&500u32.to_be_bytes()); // segment_duration
+    payload.extend_from_slice(&2112i32.to_be_bytes()); // media_time
+    payload.extend_from_slice(&1i16.to_be_bytes()); // rate_integer
+    payload.extend_from_slice(&0u16.to_be_bytes()); // rate_fraction
+
+    let entries = parse_elst(&payload, movie_timescale, media_timescale).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!((entries[0].media_start - 2112.0 / 44100.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_parse_elst_negative_media_time_is_empty_edit() {
+    // A `media_time` of -1 marks an "empty edit" (inserted silence with
+    // no backing media); it should hold at the previous segment's media
+    // position (or 0.0, for the first segment) rather than producing a
+    // nonsensical negative time.
+    let mut payload = vec![];
+    payload.extend_from_slice(&[0, 0, 0, 0]);
+    payload.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    payload.extend_from_slice(&200u32.to_be_bytes()); // segment_duration
+    payload.extend_from_slice(&(-1i32).to_be_bytes()); // media_time
+    payload.extend_from_slice(&1i16.to_be_bytes());
+    payload.extend_from_slice(&0u16.to_be_bytes());
+
+    let entries = parse_elst(&payload, 1000, 44100).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].media_start, 0.0);
+}